@@ -1,40 +1,55 @@
 use rug::Integer;
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::io::{self, Read, Write};
 
+std::thread_local! {
+    /// Memoized `p^i`, keyed by `(prime, i)`, shared by every `QpOp` for the
+    /// same prime so `add`/`int_part` don't recompute it on every call.
+    static POWER_CACHE: RefCell<HashMap<(i32, u32), Integer>> = RefCell::new(HashMap::new());
+}
+
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Qp {
     pub numerator: Integer,
     pub valuation: i16,
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct QpOp {
     prime: i32,
+    precision: u32,
 }
 
 impl QpOp {
-    pub const fn new(prime: i32) -> Self {
-        Self { prime }
+    pub const fn new(prime: i32, precision: u32) -> Self {
+        Self { prime, precision }
     }
 
     pub const fn prime(&self) -> i32 {
         self.prime
     }
 
+    /// Working precision `N`: arithmetic that needs a modulus (inversion,
+    /// division) is only correct up to `p^precision`.
+    pub const fn precision(&self) -> u32 {
+        self.precision
+    }
+
+    /// Cached `p^i`, computed once per `(prime, i)` via binary exponentiation
+    /// on a cache miss.
     pub fn power_p(&self, i: u32) -> Integer {
-        let mut res = Integer::from(1);
-        for _ in 0..i {
-            res *= self.prime;
-        }
-        res
+        POWER_CACHE.with(|cache| {
+            cache
+                .borrow_mut()
+                .entry((self.prime, i))
+                .or_insert_with(|| Integer::from(self.prime).pow(i))
+                .clone()
+        })
     }
 
     pub fn power_p_int(&self, i: u32) -> i32 {
-        let mut res = 1;
-        for _ in 0..i {
-            res *= self.prime;
-        }
-        res
+        self.power_p(i).to_i32_wrapping()
     }
 
     pub fn simplify(&self, x: &mut Qp) {
@@ -54,38 +69,100 @@ impl QpOp {
         format!("{}({})", x.numerator, x.valuation)
     }
 
+    /// Canonical variable-length encoding: the valuation and the numerator's
+    /// byte length are zigzag/LEB128 varints, so arbitrarily large numerators
+    /// serialize without a fixed-width ceiling and the stream is
+    /// self-describing.
     pub fn save(&self, x: &Qp, writer: &mut impl Write) -> io::Result<()> {
-        let numerator_bytes = self.save_to_vec(&x.numerator);
+        Self::write_varint(writer, Self::zigzag_encode(i64::from(x.valuation)))?;
 
-        writer.write_all(&x.valuation.to_le_bytes())?;
-
-        let len = i16::try_from(numerator_bytes.len())
-            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "numerator too long"))?;
-        writer.write_all(&len.to_le_bytes())?;
-        writer.write_all(&numerator_bytes)?;
-        Ok(())
+        let magnitude = Self::zigzag_encode_integer(&x.numerator);
+        let bytes = Self::integer_to_bytes(&magnitude);
+        Self::write_varint(writer, bytes.len() as u64)?;
+        writer.write_all(&bytes)
     }
 
     pub fn load(&self, reader: &mut impl Read) -> io::Result<Qp> {
-        let mut valuation_bytes = [0u8; 2];
-        reader.read_exact(&mut valuation_bytes)?;
-        let valuation = i16::from_le_bytes(valuation_bytes);
+        let valuation = Self::zigzag_decode(Self::read_varint(reader)?);
+        let valuation = i16::try_from(valuation)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "valuation out of range"))?;
 
-        let mut len_bytes = [0u8; 2];
-        reader.read_exact(&mut len_bytes)?;
-        let len = i16::from_le_bytes(len_bytes);
+        let len = Self::read_varint(reader)?;
         let len = usize::try_from(len)
             .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid numerator length"))?;
 
-        let mut buf = vec![0u8; len];
-        reader.read_exact(&mut buf)?;
+        let mut buf = Vec::new();
+        reader.take(len as u64).read_to_end(&mut buf)?;
+        if buf.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "truncated numerator",
+            ));
+        }
 
+        let magnitude = Self::integer_from_bytes(&buf);
         Ok(Qp {
-            numerator: self.load_from_vec(&buf),
+            numerator: Self::zigzag_decode_integer(&magnitude),
             valuation,
         })
     }
 
+    fn write_varint(writer: &mut impl Write, mut value: u64) -> io::Result<()> {
+        loop {
+            let mut byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value != 0 {
+                byte |= 0x80;
+            }
+            writer.write_all(&[byte])?;
+            if value == 0 {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_varint(reader: &mut impl Read) -> io::Result<u64> {
+        let mut result: u64 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let mut byte = [0u8; 1];
+            reader.read_exact(&mut byte)?;
+            let byte = byte[0];
+            if shift >= 64 || (shift == 63 && byte > 1) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "varint overflow"));
+            }
+            result |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(result);
+            }
+            shift += 7;
+        }
+    }
+
+    fn zigzag_encode(value: i64) -> u64 {
+        ((value << 1) ^ (value >> 63)) as u64
+    }
+
+    fn zigzag_decode(value: u64) -> i64 {
+        ((value >> 1) as i64) ^ -((value & 1) as i64)
+    }
+
+    fn zigzag_encode_integer(x: &Integer) -> Integer {
+        if *x >= 0 {
+            Integer::from(x * 2)
+        } else {
+            Integer::from(x * -2) - 1
+        }
+    }
+
+    fn zigzag_decode_integer(z: &Integer) -> Integer {
+        if z.is_even() {
+            Integer::from(z / 2i32)
+        } else {
+            -(Integer::from(z + 1) / 2i32)
+        }
+    }
+
     pub fn add(&self, x: &Qp, y: &Qp) -> Qp {
         if x.valuation < y.valuation {
             return Qp {
@@ -149,8 +226,82 @@ impl QpOp {
         !self.is_zero(x)
     }
 
-    pub fn inverse(&self, _x: &Qp) -> Qp {
-        panic!("not implemented");
+    /// Inverts `x` modulo `p^precision` by Hensel/Newton lifting.
+    ///
+    /// `x` is first reduced to `p^v * n` with `n` coprime to `p`; then
+    /// `y = n^-1 mod p^precision` is found by doubling precision from a
+    /// seed `n^-1 mod p` via `y <- y * (2 - n*y)`, and the result is scaled
+    /// back by `p^-v`.
+    pub fn inverse(&self, x: &Qp) -> Qp {
+        if self.is_zero(x) {
+            panic!("cannot invert zero in Qp");
+        }
+
+        let mut unit = x.clone();
+        self.simplify(&mut unit);
+        let n = unit.numerator.clone();
+
+        let mut y = self.inverse_mod_p(&n);
+        let mut modulus = Integer::from(self.prime);
+        let target = self.power_p(self.precision);
+
+        while modulus < target {
+            modulus = Integer::from(&modulus * &modulus);
+            let correction = Integer::from(2) - Integer::from(&n * &y);
+            y = Integer::from(&y * &correction);
+            y = Self::balance(y, &modulus);
+        }
+        y = Self::balance(y, &target);
+
+        Qp {
+            numerator: y,
+            valuation: -unit.valuation,
+        }
+    }
+
+    pub fn divide(&self, x: &Qp, y: &Qp) -> Qp {
+        self.multiply(x, &self.inverse(y))
+    }
+
+    /// `a^-1 mod p`, via the extended Euclidean algorithm.
+    fn inverse_mod_p(&self, a: &Integer) -> Integer {
+        let p = Integer::from(self.prime);
+        let (_, x, _) = Self::extended_gcd(a.clone(), p.clone());
+        let mut y = x % &p;
+        if y < 0 {
+            y += &p;
+        }
+        y
+    }
+
+    /// Returns `(g, x, y)` with `a*x + b*y = g = gcd(a, b)` and `g >= 0`,
+    /// regardless of the sign of `a` or `b`.
+    fn extended_gcd(a: Integer, b: Integer) -> (Integer, Integer, Integer) {
+        if b.is_zero() {
+            return if a < 0 {
+                (-a, Integer::from(-1), Integer::from(0))
+            } else {
+                (a, Integer::from(1), Integer::from(0))
+            };
+        }
+        let q = Integer::from(&a / &b);
+        let r = Integer::from(&a % &b);
+        let (g, x1, y1) = Self::extended_gcd(b, r);
+        let x = y1.clone();
+        let y = x1 - &q * &y1;
+        (g, x, y)
+    }
+
+    /// Reduces `y` into the balanced/symmetric range `(-modulus/2, modulus/2]`.
+    fn balance(y: Integer, modulus: &Integer) -> Integer {
+        let mut r = y % modulus;
+        if r < 0 {
+            r += modulus;
+        }
+        if Integer::from(&r * 2) > *modulus {
+            r -= modulus;
+        }
+        r
     }
 
     pub fn construct(&self, num: i32, val: i16) -> Qp {
@@ -182,41 +333,355 @@ impl QpOp {
         self.int_part(x).to_string()
     }
 
-    fn save_to_vec(&self, x: &Integer) -> Vec<u8> {
-        if x.is_zero() {
-            return vec![0];
+    /// Renders `x` as its base-`p` digit expansion to `precision` digits,
+    /// i.e. digits `a_i` with `x ≡ Σ a_i p^i (mod p^(valuation+precision))`,
+    /// behind an explicit `"{p}^{v} * ..."` marker (a radix point separates
+    /// the `p^(<0)` digits from the rest when `v` is negative). The marker
+    /// always carries the true valuation, even when `precision` is too
+    /// small to print `-valuation` fractional digits.
+    ///
+    /// Each digit is rendered as a single decimal character with no
+    /// delimiter, so this only supports `prime < 10`; larger primes would
+    /// make the digit string ambiguous to re-parse.
+    pub fn output_padic(&self, x: &Qp, precision: u32) -> String {
+        assert!(
+            self.prime < 10,
+            "p-adic digit formatting only supports primes < 10, got {}",
+            self.prime
+        );
+
+        let mut unit = x.clone();
+        self.simplify(&mut unit);
+
+        if self.is_zero(&unit) {
+            return "0".to_string();
         }
 
-        let mut result = Vec::new();
-        result.push(if x > &Integer::ZERO { 1 } else { 2 });
+        let digits = self.digit_expansion(&unit.numerator, precision);
+        let marker = format!("{}^{} * ", self.prime, unit.valuation);
+
+        if unit.valuation < 0 {
+            let frac_len = ((-unit.valuation) as usize).min(digits.len());
+            let (frac, int_part) = digits.split_at(frac_len);
+            let int_str: String = if int_part.is_empty() {
+                "0".to_string()
+            } else {
+                int_part.iter().rev().map(u8::to_string).collect()
+            };
+            let frac_str: String = frac.iter().rev().map(u8::to_string).collect();
+            format!("{marker}{int_str}.{frac_str}")
+        } else {
+            let digit_str: String = digits.iter().rev().map(u8::to_string).collect();
+            format!("{marker}{digit_str}")
+        }
+    }
 
-        let mut n = x.abs();
+    /// Parses the notation produced by [`QpOp::output_padic`] back into a `Qp`.
+    ///
+    /// Like `output_padic`, this only supports `prime < 10` (see its doc
+    /// comment): for larger primes a multi-digit coefficient is
+    /// indistinguishable from several single-digit ones.
+    pub fn parse_padic(&self, s: &str) -> Result<Qp, String> {
+        if self.prime >= 10 {
+            return Err(format!(
+                "p-adic digit parsing only supports primes < 10, got {}",
+                self.prime
+            ));
+        }
+
+        let s = s.trim();
+        if s == "0" {
+            return Ok(self.zero());
+        }
+
+        let rest = s
+            .strip_prefix(&format!("{}^", self.prime))
+            .ok_or_else(|| format!("expected '{}^v * ...', got {s:?}", self.prime))?;
+        let (valuation_str, payload) = rest
+            .split_once('*')
+            .ok_or_else(|| format!("expected '{}^v * ...', got {s:?}", self.prime))?;
+        let valuation: i16 = valuation_str
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid valuation in {s:?}"))?;
+        let payload = payload.trim();
+
+        let numerator = if let Some((int_str, frac_str)) = payload.split_once('.') {
+            let scale = self.power_p(frac_str.len() as u32);
+            self.digits_to_integer(int_str)? * scale + self.digits_to_integer(frac_str)?
+        } else {
+            self.digits_to_integer(payload)?
+        };
+
+        Ok(Qp { numerator, valuation })
+    }
+
+    /// `digits[i]` is the coefficient of `p^i` in `n`'s base-`p` expansion,
+    /// found by repeatedly taking the unit mod `p` and dividing.
+    fn digit_expansion(&self, n: &Integer, precision: u32) -> Vec<u8> {
+        let p = Integer::from(self.prime);
+        let mut n = n.clone();
+        let mut digits = Vec::with_capacity(precision as usize);
+        for _ in 0..precision {
+            let mut digit = Integer::from(&n % &p);
+            if digit < 0 {
+                digit += &p;
+            }
+            n = Integer::from(&n - &digit) / &p;
+            digits.push(digit.to_u32_wrapping() as u8);
+        }
+        digits
+    }
+
+    /// Parses a most-significant-digit-first base-`p` string into an `Integer`.
+    fn digits_to_integer(&self, s: &str) -> Result<Integer, String> {
+        let p = Integer::from(self.prime);
+        let mut value = Integer::new();
+        for c in s.chars() {
+            let digit = c
+                .to_digit(10)
+                .ok_or_else(|| format!("invalid p-adic digit {c:?}"))?;
+            if digit as i32 >= self.prime {
+                return Err(format!("digit {digit} out of range for base {}", self.prime));
+            }
+            value = value * &p + digit;
+        }
+        Ok(value)
+    }
+
+    /// Base-256, little-endian magnitude bytes of a non-negative `Integer`
+    /// (used for the zigzagged numerator, which is always non-negative).
+    fn integer_to_bytes(x: &Integer) -> Vec<u8> {
+        let mut n = x.clone();
+        let mut result = Vec::new();
         while !n.is_zero() {
             let digit = (&n % 256u32).to_u32_wrapping() as u8;
             result.push(digit);
             n /= 256u32;
         }
-
         result
     }
 
-    fn load_from_vec(&self, buf: &[u8]) -> Integer {
-        if buf.is_empty() {
-            return Integer::new();
-        }
-
+    fn integer_from_bytes(buf: &[u8]) -> Integer {
         let mut res = Integer::new();
         let mut vl = Integer::from(1);
-        for &b in buf.iter().skip(1) {
+        for &b in buf {
             res += &vl * Integer::from(b);
             vl *= 256u32;
         }
-
-        if buf[0] == 2 {
-            res = -res;
-        }
         res
     }
 }
 
-pub const Q3_OP: QpOp = QpOp { prime: 3 };
+pub const Q3_OP: QpOp = QpOp::new(3, 40);
+
+/// A `Qp` value paired with the `QpOp` it belongs to, so it can implement
+/// `std::ops` and `num_traits` and be dropped into generic numeric code
+/// instead of routing every operation through explicit `QpOp` calls.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FpElem {
+    op: QpOp,
+    value: Qp,
+}
+
+impl FpElem {
+    pub fn new(op: QpOp, value: Qp) -> Self {
+        Self { op, value }
+    }
+
+    pub const fn op(&self) -> QpOp {
+        self.op
+    }
+
+    pub fn value(&self) -> &Qp {
+        &self.value
+    }
+
+    fn check_compatible(&self, other: &Self) {
+        assert_eq!(
+            self.op.prime(),
+            other.op.prime(),
+            "cannot combine FpElem values from different primes"
+        );
+    }
+
+    /// The additive identity for `op`'s prime. Not `num_traits::Zero`,
+    /// since that trait has no instance to read a prime from and generic
+    /// code chaining it across `FpElem`s at different primes would panic
+    /// in `check_compatible` the moment they're combined.
+    pub fn identity(op: QpOp) -> Self {
+        FpElem::new(op, op.zero())
+    }
+
+    /// The multiplicative identity for `op`'s prime. Same caveat as
+    /// [`FpElem::identity`] with respect to `num_traits::One`.
+    pub fn one(op: QpOp) -> Self {
+        FpElem::new(op, op.unit(1))
+    }
+}
+
+impl std::ops::Add for FpElem {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        self.check_compatible(&rhs);
+        FpElem::new(self.op, self.op.add(&self.value, &rhs.value))
+    }
+}
+
+impl std::ops::Sub for FpElem {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self.check_compatible(&rhs);
+        let neg_rhs = self.op.minus(&rhs.value);
+        FpElem::new(self.op, self.op.add(&self.value, &neg_rhs))
+    }
+}
+
+impl std::ops::Mul for FpElem {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        self.check_compatible(&rhs);
+        FpElem::new(self.op, self.op.multiply(&self.value, &rhs.value))
+    }
+}
+
+impl std::ops::Div for FpElem {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        self.check_compatible(&rhs);
+        FpElem::new(self.op, self.op.divide(&self.value, &rhs.value))
+    }
+}
+
+impl std::ops::Neg for FpElem {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        let value = self.op.minus(&self.value);
+        FpElem::new(self.op, value)
+    }
+}
+
+impl num_traits::Inv for FpElem {
+    type Output = Self;
+
+    fn inv(self) -> Self {
+        let value = self.op.inverse(&self.value);
+        FpElem::new(self.op, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_congruent_to_one(product: &Qp) {
+        let modulus = Q3_OP.power_p(Q3_OP.precision());
+        let mut remainder =
+            Integer::from(&product.numerator * Q3_OP.power_p(product.valuation as u32));
+        remainder %= &modulus;
+        if remainder < 0 {
+            remainder += &modulus;
+        }
+        assert_eq!(remainder, Integer::from(1));
+    }
+
+    #[test]
+    fn multiply_inverse_is_one_mod_p_pow_precision() {
+        for n in [1, 2, 4, 5, 7, 8, -1, -2, -4, -5, -7, -8] {
+            let x = Q3_OP.construct(n, 0);
+            let inv = Q3_OP.inverse(&x);
+            let product = Q3_OP.multiply(&x, &inv);
+            assert_congruent_to_one(&product);
+        }
+    }
+
+    #[test]
+    fn divide_by_self_is_one() {
+        for n in [1, -1, 2, -2, 5, -5] {
+            let x = Q3_OP.construct(n, 0);
+            let quotient = Q3_OP.divide(&x, &x);
+            assert_congruent_to_one(&quotient);
+        }
+    }
+
+    #[test]
+    fn save_load_round_trips_zero_and_small_values() {
+        let cases = [
+            Qp { numerator: Integer::from(0), valuation: 0 },
+            Qp { numerator: Integer::from(1), valuation: 0 },
+            Qp { numerator: Integer::from(-1), valuation: -5 },
+            Qp { numerator: Integer::from(12345), valuation: 7 },
+        ];
+
+        for x in cases {
+            let mut buf = Vec::new();
+            Q3_OP.save(&x, &mut buf).unwrap();
+            let loaded = Q3_OP.load(&mut &buf[..]).unwrap();
+            assert_eq!(loaded, x);
+        }
+    }
+
+    #[test]
+    fn save_load_round_trips_numerators_beyond_the_old_i16_cap() {
+        // The old format capped the numerator's byte length at `i16::MAX`;
+        // this exceeds it in both sign directions.
+        let huge = Integer::from(256u32).pow(40_000);
+        let cases = [
+            Qp { numerator: huge.clone(), valuation: 100 },
+            Qp { numerator: -huge, valuation: -100 },
+        ];
+
+        for x in cases {
+            let mut buf = Vec::new();
+            Q3_OP.save(&x, &mut buf).unwrap();
+            assert!(
+                buf.len() > i16::MAX as usize,
+                "expected the encoded numerator to exceed the old i16 length cap"
+            );
+            let loaded = Q3_OP.load(&mut &buf[..]).unwrap();
+            assert_eq!(loaded, x);
+        }
+    }
+
+    #[test]
+    fn output_padic_parse_padic_round_trips_up_to_precision() {
+        let cases = [
+            Q3_OP.construct(1, 0),
+            Q3_OP.construct(5, 3),
+            Q3_OP.construct(7, -3),
+            Q3_OP.construct(-7, -3),
+        ];
+
+        for x in cases {
+            let rendered = Q3_OP.output_padic(&x, 10);
+            let parsed = Q3_OP.parse_padic(&rendered).unwrap();
+            let mut simplified = x.clone();
+            Q3_OP.simplify(&mut simplified);
+            // Digits are in `0..p`, so the numerator comes back as its
+            // residue in `[0, p^10)`: -7 is rendered as ...2222202 and
+            // parses back as 3^10 - 7.
+            let modulus = Q3_OP.power_p(10);
+            simplified.numerator %= &modulus;
+            if simplified.numerator < 0 {
+                simplified.numerator += &modulus;
+            }
+            assert_eq!(parsed, simplified, "round trip of {rendered:?}");
+        }
+    }
+
+    #[test]
+    fn output_padic_preserves_valuation_when_precision_is_smaller_than_minus_valuation() {
+        // `precision` (10) is smaller than `-valuation` (15): inferring the
+        // valuation from the printed fractional digit count would give -10
+        // instead of -15.
+        let x = Q3_OP.construct(1, -15);
+        let rendered = Q3_OP.output_padic(&x, 10);
+        let parsed = Q3_OP.parse_padic(&rendered).unwrap();
+        assert_eq!(parsed.valuation, -15, "rendered as {rendered:?}");
+    }
+}